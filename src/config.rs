@@ -0,0 +1,75 @@
+// Copyright © SurrealDB Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module stores the configuration used to open a database.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Where a [`Database`](crate::Database) keeps its committed data
+#[derive(Clone, Debug)]
+pub enum PersistType {
+	/// Keep all data in memory only; nothing survives a process restart
+	Memory,
+	/// Persist data to a file on disk, reloading it the next time it is opened
+	File(PathBuf),
+}
+
+/// When a [`Database`](crate::Database) folds its write-ahead log into a snapshot
+#[derive(Clone, Copy, Debug)]
+pub enum SyncPolicy {
+	/// Never fold the write-ahead log automatically; call `flush` explicitly
+	Never,
+	/// Fold the write-ahead log into a snapshot after every commit
+	OnCommit,
+	/// Fold the write-ahead log into a snapshot on a fixed interval
+	Periodic(Duration),
+}
+
+/// Configuration used to open a [`Database`](crate::Database)
+#[derive(Clone, Debug)]
+pub struct Config {
+	/// Where this database persists its data
+	pub persist: PersistType,
+	/// When this database flushes its write-ahead log to a snapshot
+	pub sync: SyncPolicy,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			persist: PersistType::Memory,
+			sync: SyncPolicy::Never,
+		}
+	}
+}
+
+impl Config {
+	/// Create a configuration for an in-memory only database
+	pub fn memory() -> Config {
+		Config::default()
+	}
+	/// Create a configuration which persists to the given file path
+	pub fn file(path: impl Into<PathBuf>) -> Config {
+		Config {
+			persist: PersistType::File(path.into()),
+			sync: SyncPolicy::OnCommit,
+		}
+	}
+	/// Set the sync policy used to flush the write-ahead log to a snapshot
+	pub fn with_sync(mut self, sync: SyncPolicy) -> Config {
+		self.sync = sync;
+		self
+	}
+}
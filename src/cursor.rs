@@ -0,0 +1,123 @@
+// Copyright © SurrealDB Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module stores the lazy cursor used to iterate a transaction's snapshot.
+
+use imbl::ordmap::Iter;
+use imbl::OrdMap;
+use imbl::OrdSet;
+use std::ops::Range;
+
+/// The direction a [`Cursor`] advances in when calling `next`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+	/// Advance from the start of the range towards the end
+	Forward,
+	/// Advance from the end of the range towards the start
+	Reverse,
+}
+
+/// A lazy, bidirectional cursor over a range of a transaction's snapshot
+///
+/// Unlike `keys`/`scan`, a cursor does not materialize its results up front;
+/// entries are only visited as `next`/`prev` are called.
+pub struct Cursor<'a, K, V>
+where
+	K: Ord + Clone + 'a,
+{
+	snapshot: &'a OrdMap<K, V>,
+	/// The lower bound this cursor was originally constructed with, fixed for
+	/// the cursor's lifetime so repeated `seek` calls never undershoot it
+	origin: K,
+	range: Range<K>,
+	direction: Direction,
+	/// The read-set of the transaction this cursor was obtained from, if any,
+	/// recorded into as each entry is visited
+	reads: Option<&'a mut OrdSet<K>>,
+	iter: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Cursor<'a, K, V>
+where
+	K: Ord + Clone + 'a,
+	V: Clone + 'a,
+{
+	/// Create a new cursor over `range`, advancing in `direction` by default
+	///
+	/// If `reads` is given, every key this cursor yields via `next`/`prev` is
+	/// recorded into it, so a write transaction's optimistic read-set stays
+	/// accurate even when the cursor API is used directly.
+	pub(crate) fn new(
+		snapshot: &'a OrdMap<K, V>,
+		range: Range<K>,
+		direction: Direction,
+		reads: Option<&'a mut OrdSet<K>>,
+	) -> Cursor<'a, K, V> {
+		let iter = snapshot.range(range.clone());
+		Cursor {
+			snapshot,
+			origin: range.start.clone(),
+			range,
+			direction,
+			reads,
+			iter,
+		}
+	}
+
+	/// Change the direction this cursor advances in on subsequent calls to `next`
+	pub fn direction(&mut self, direction: Direction) {
+		self.direction = direction;
+	}
+
+	/// Reposition the cursor at the first entry greater than or equal to `key`
+	pub fn seek(&mut self, key: K) {
+		// Never reposition before the cursor's original lower bound, but always
+		// honour `key` against that fixed bound rather than the last seek
+		let start = if key > self.origin { key } else { self.origin.clone() };
+		self.range.start = start.clone();
+		self.iter = self.snapshot.range(start..self.range.end.clone());
+	}
+
+	/// Advance the cursor in its current direction, returning the next entry
+	#[allow(clippy::should_implement_trait)]
+	pub fn next(&mut self) -> Option<(&'a K, &'a V)> {
+		let item = match self.direction {
+			Direction::Forward => self.iter.next(),
+			Direction::Reverse => self.iter.next_back(),
+		};
+		self.record(item)
+	}
+
+	/// Step the cursor opposite to its current direction, returning that entry
+	///
+	/// `next` and `prev` take entries from the two opposite ends of the range
+	/// not yet visited, so `prev` returns the entry at the far end of that
+	/// range, not the entry before the one `next` last returned; no entry is
+	/// returned twice until the cursor is seeked again.
+	pub fn prev(&mut self) -> Option<(&'a K, &'a V)> {
+		let item = match self.direction {
+			Direction::Forward => self.iter.next_back(),
+			Direction::Reverse => self.iter.next(),
+		};
+		self.record(item)
+	}
+
+	/// Record a yielded entry's key into the transaction's read-set, if tracked
+	fn record(&mut self, item: Option<(&'a K, &'a V)>) -> Option<(&'a K, &'a V)> {
+		if let (Some((key, _)), Some(reads)) = (&item, self.reads.as_mut()) {
+			reads.insert((*key).clone());
+		}
+		item
+	}
+}
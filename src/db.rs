@@ -14,10 +14,19 @@
 
 //! This module stores the core in-memory database type.
 
+use crate::config::{Config, PersistType, SyncPolicy};
+use crate::err::Error;
+use crate::sub::{ChangeSet, Handle, Subscriptions};
 use crate::tx::Transaction;
+use crate::wal;
 use arc_swap::ArcSwap;
 use imbl::OrdMap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::fmt::Debug;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -28,10 +37,43 @@ where
 	K: Ord + Clone + Debug + Sync + Send + 'static,
 	V: Eq + Clone + Debug + Sync + Send + 'static,
 {
-	/// The datastore transaction write lock
+	/// The datastore commit lock, held only while a transaction is committing
 	pub(crate) writelock: Arc<Mutex<()>>,
 	/// The underlying copy-on-write B-tree datastructure
 	pub(crate) datastore: Arc<ArcSwap<OrdMap<K, V>>>,
+	/// The commit version each key was last written at, used to detect
+	/// conflicts even if a key's value has since changed back to what it was
+	/// when a transaction began
+	pub(crate) versions: Arc<ArcSwap<OrdMap<K, u64>>>,
+	/// A monotonic version, bumped on every successful commit
+	pub(crate) version: Arc<AtomicU64>,
+	/// The durability subsystem for this database, if it was opened against a file
+	pub(crate) persistence: Option<Arc<Persistence<K, V>>>,
+	/// The registry of observers notified with the diff of every successful commit
+	pub(crate) subscriptions: Subscriptions<K, V>,
+}
+
+/// Appends a transaction's write-set to the write-ahead log
+pub(crate) type AppendFn<K, V> = fn(&mut std::fs::File, &OrdMap<K, Option<V>>) -> Result<(), Error>;
+/// Folds the write-ahead log into a full snapshot file
+pub(crate) type FoldFn<K, V> = fn(&std::path::Path, &OrdMap<K, V>) -> Result<(), Error>;
+
+/// The durable write-ahead log state for a file-backed [`Database`]
+pub(crate) struct Persistence<K, V>
+where
+	K: Ord + Clone + Debug + Sync + Send + 'static,
+	V: Eq + Clone + Debug + Sync + Send + 'static,
+{
+	/// The base file path this database was opened against
+	pub(crate) base: PathBuf,
+	/// The configured sync policy
+	pub(crate) sync: SyncPolicy,
+	/// The open write-ahead log file, appended to on every commit
+	pub(crate) wal: Mutex<std::fs::File>,
+	/// Appends a transaction's write-set to the write-ahead log
+	pub(crate) append: AppendFn<K, V>,
+	/// Folds the write-ahead log into a full snapshot file
+	pub(crate) fold: FoldFn<K, V>,
 }
 
 /// Create a new transactional in-memory database
@@ -42,8 +84,67 @@ where
 {
 	Database {
 		datastore: Arc::new(ArcSwap::new(Arc::new(OrdMap::new()))),
+		versions: Arc::new(ArcSwap::new(Arc::new(OrdMap::new()))),
+		writelock: Arc::new(Mutex::new(())),
+		version: Arc::new(AtomicU64::new(0)),
+		persistence: None,
+		subscriptions: Subscriptions::new(),
+	}
+}
+
+/// Open a database using the given configuration
+///
+/// With [`PersistType::Memory`] this behaves exactly like [`new`]. With
+/// [`PersistType::File`] the last committed snapshot and any trailing
+/// write-ahead log records are replayed from disk to rebuild the datastore,
+/// and every subsequent commit is appended to that log according to the
+/// configured [`SyncPolicy`]. [`SyncPolicy::Periodic`] spawns a background
+/// task which stops on its own once every clone of the returned [`Database`]
+/// has been dropped, so it never outlives its database.
+pub async fn open<K, V>(cfg: Config) -> Result<Database<K, V>, Error>
+where
+	K: Ord + Clone + Debug + Sync + Send + Serialize + DeserializeOwned + 'static,
+	V: Eq + Clone + Debug + Sync + Send + Serialize + DeserializeOwned + 'static,
+{
+	let path = match cfg.persist {
+		PersistType::Memory => return Ok(new()),
+		PersistType::File(path) => path,
+	};
+	// Rebuild the datastore from the last snapshot and any trailing log records
+	let map = wal::load::<K, V>(&path)?;
+	let file = wal::open_wal(&path)?;
+	let db = Database {
+		datastore: Arc::new(ArcSwap::new(Arc::new(map))),
+		versions: Arc::new(ArcSwap::new(Arc::new(OrdMap::new()))),
 		writelock: Arc::new(Mutex::new(())),
+		version: Arc::new(AtomicU64::new(0)),
+		persistence: Some(Arc::new(Persistence {
+			base: path,
+			sync: cfg.sync,
+			wal: Mutex::new(file),
+			append: wal::append::<K, V>,
+			fold: wal::fold::<K, V>,
+		})),
+		subscriptions: Subscriptions::new(),
+	};
+	// Periodic sync policies need a background task to fold the log over time
+	if let SyncPolicy::Periodic(interval) = cfg.sync {
+		let db = db.clone();
+		tokio::spawn(async move {
+			loop {
+				tokio::time::sleep(interval).await;
+				// `db` here is this task's own clone of the database, so a
+				// strong count of one means every other handle has been
+				// dropped and this database can never be written to or
+				// flushed again; stop rather than looping forever
+				if Arc::strong_count(&db.writelock) <= 1 {
+					break;
+				}
+				let _ = db.flush().await;
+			}
+		});
 	}
+	Ok(db)
 }
 
 impl<K, V> Database<K, V>
@@ -52,18 +153,43 @@ where
 	V: Eq + Clone + Debug + Sync + Send + 'static,
 {
 	/// Start a new read-only or writeable transaction
+	///
+	/// Write transactions no longer hold a lock for their entire lifetime.
+	/// Instead they run optimistically, tracking the keys they read and
+	/// write, and only contend for a lock briefly at commit time.
 	pub async fn begin(&self, write: bool) -> Transaction<K, V> {
 		match write {
-			true => {
-				let lock = Some(self.writelock.clone().lock_owned().await);
-				Transaction::write(self.clone(), lock)
-			}
-			false => {
-				let lock = None;
-				Transaction::read(self.clone(), lock)
-			}
+			true => Transaction::write(self.clone()),
+			false => Transaction::read(self.clone()),
 		}
 	}
+
+	/// Fold the write-ahead log into a full snapshot on disk
+	///
+	/// This is a no-op for databases opened with [`PersistType::Memory`]. The
+	/// fold is guarded by the write-ahead log's own lock, the same lock a
+	/// commit holds while appending, so it can't race a concurrent append.
+	pub async fn flush(&self) -> Result<(), Error> {
+		if let Some(p) = &self.persistence {
+			let _wal = p.wal.lock().await;
+			let current = self.datastore.load();
+			(p.fold)(&p.base, &current)?;
+		}
+		Ok(())
+	}
+
+	/// Register an observer for a range of keys
+	///
+	/// The callback is invoked after every write transaction commits with the
+	/// subset of that commit's [`ChangeSet`](crate::ChangeSet) which falls
+	/// within `range`. Cancelled transactions never trigger a notification.
+	/// Dropping the returned handle unregisters the observer.
+	pub fn observe<F>(&self, range: Range<K>, callback: F) -> Handle<K, V>
+	where
+		F: Fn(&ChangeSet<K, V>) + Send + Sync + 'static,
+	{
+		self.subscriptions.register(range, callback)
+	}
 }
 
 #[cfg(test)]
@@ -71,6 +197,7 @@ mod tests {
 
 	use super::*;
 	use crate::kv::{Key, Val};
+	use crate::Error;
 
 	#[tokio::test]
 	async fn begin_tx_readable() {
@@ -92,7 +219,7 @@ mod tests {
 		assert!(res.is_ok());
 		let res = async { tx.get("test") }.await;
 		assert!(res.is_ok());
-		let res = async { tx.commit() }.await;
+		let res = async { tx.commit().await }.await;
 		assert!(res.is_ok());
 	}
 
@@ -107,7 +234,7 @@ mod tests {
 		assert!(res.is_err());
 		let res = tx.del("test");
 		assert!(res.is_err());
-		let res = tx.commit();
+		let res = tx.commit().await;
 		assert!(res.is_err());
 		let res = tx.cancel();
 		assert!(res.is_ok());
@@ -126,7 +253,7 @@ mod tests {
 		assert!(res.is_err());
 		let res = tx.del("test");
 		assert!(res.is_err());
-		let res = tx.commit();
+		let res = tx.commit().await;
 		assert!(res.is_err());
 		let res = tx.cancel();
 		assert!(res.is_err());
@@ -164,7 +291,7 @@ mod tests {
 		assert_eq!(res, true);
 		let res = tx.get("test").unwrap();
 		assert_eq!(res, Some("something"));
-		let res = tx.commit();
+		let res = tx.commit().await;
 		assert!(res.is_ok());
 		// ----------
 		let mut tx = db.begin(false).await;
@@ -186,7 +313,7 @@ mod tests {
 		assert_eq!(res, true);
 		let res = tx.get("test").unwrap();
 		assert_eq!(res, Some("something"));
-		let res = tx.commit();
+		let res = tx.commit().await;
 		assert!(res.is_ok());
 		// ----------
 		let mut tx1 = db.begin(false).await;
@@ -217,7 +344,7 @@ mod tests {
 		assert_eq!(res, true);
 		let res = tx.get("test").unwrap();
 		assert_eq!(res, Some("something"));
-		let res = tx.commit();
+		let res = tx.commit().await;
 		assert!(res.is_ok());
 		// ----------
 		let mut tx1 = db.begin(false).await;
@@ -232,7 +359,7 @@ mod tests {
 		assert_eq!(res, true);
 		let res = txw.exists("temp").unwrap();
 		assert_eq!(res, true);
-		let res = txw.commit();
+		let res = txw.commit().await;
 		assert!(res.is_ok());
 		// ----------
 		let mut tx2 = db.begin(false).await;
@@ -249,4 +376,414 @@ mod tests {
 		let res = tx2.cancel();
 		assert!(res.is_ok());
 	}
+
+	#[tokio::test]
+	async fn disjoint_writers_do_not_conflict() {
+		let db: Database<&str, &str> = new();
+		// ----------
+		let mut tx1 = db.begin(true).await;
+		let mut tx2 = db.begin(true).await;
+		tx1.set("one", "a").unwrap();
+		tx2.set("two", "b").unwrap();
+		let res = tx1.commit().await;
+		assert!(res.is_ok());
+		let res = tx2.commit().await;
+		assert!(res.is_ok());
+		// ----------
+		let mut tx = db.begin(false).await;
+		assert_eq!(tx.get("one").unwrap(), Some("a"));
+		assert_eq!(tx.get("two").unwrap(), Some("b"));
+		tx.cancel().unwrap();
+	}
+
+	#[tokio::test]
+	async fn overlapping_writers_conflict() {
+		let db: Database<&str, &str> = new();
+		let mut tx = db.begin(true).await;
+		tx.set("test", "initial").unwrap();
+		tx.commit().await.unwrap();
+		// ----------
+		let mut tx1 = db.begin(true).await;
+		let mut tx2 = db.begin(true).await;
+		assert_eq!(tx1.get("test").unwrap(), Some("initial"));
+		assert_eq!(tx2.get("test").unwrap(), Some("initial"));
+		tx1.set("test", "from-tx1").unwrap();
+		tx2.set("test", "from-tx2").unwrap();
+		let res = tx1.commit().await;
+		assert!(res.is_ok());
+		let res = tx2.commit().await;
+		assert!(matches!(res, Err(Error::Conflict)));
+	}
+
+	#[tokio::test]
+	async fn aba_write_still_conflicts() {
+		let db: Database<&str, &str> = new();
+		let mut tx = db.begin(true).await;
+		tx.set("test", "a").unwrap();
+		tx.commit().await.unwrap();
+		// ----------
+		// A long-running reader takes its snapshot while "test" is "a"
+		let mut reader = db.begin(true).await;
+		assert_eq!(reader.get("test").unwrap(), Some("a"));
+		// ----------
+		// Another writer changes "test" away from "a" and back to "a" again,
+		// bumping its commit version twice, before the reader commits
+		let mut tx1 = db.begin(true).await;
+		tx1.set("test", "b").unwrap();
+		tx1.commit().await.unwrap();
+		let mut tx2 = db.begin(true).await;
+		tx2.set("test", "a").unwrap();
+		tx2.commit().await.unwrap();
+		// ----------
+		// The reader's snapshot still matches "test" by value, but the key was
+		// written twice behind its back, so its commit must still conflict
+		reader.set("other", "unrelated").unwrap();
+		let res = reader.commit().await;
+		assert!(matches!(res, Err(Error::Conflict)));
+	}
+
+	#[tokio::test]
+	async fn observers_receive_commit_diffs() {
+		let db: Database<&str, &str> = new();
+		let seen: Arc<std::sync::Mutex<Vec<crate::ChangeSet<&str, &str>>>> =
+			Arc::new(std::sync::Mutex::new(Vec::new()));
+		// ----------
+		let inside = seen.clone();
+		let _handle = db.observe("a".."n", move |changes| {
+			inside.lock().unwrap().push(changes.clone());
+		});
+		// ----------
+		let mut tx = db.begin(true).await;
+		tx.set("apple", "one").unwrap();
+		tx.set("zebra", "ignored").unwrap();
+		tx.commit().await.unwrap();
+		// ----------
+		let seen = seen.lock().unwrap();
+		assert_eq!(seen.len(), 1);
+		assert_eq!(seen[0].added, vec![("apple", "one")]);
+	}
+
+	#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+	async fn slow_observer_does_not_block_other_writers() {
+		let db: Database<&str, &str> = new();
+		let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+		let release_rx = Arc::new(std::sync::Mutex::new(release_rx));
+		let entered = Arc::new(tokio::sync::Notify::new());
+		// ----------
+		let inside = entered.clone();
+		// Only interested in "a", so the second writer's disjoint key below
+		// never reaches this callback and blocks on it a second time
+		let _handle = db.observe("a".."b", move |_changes| {
+			inside.notify_one();
+			// Block the callback until the test explicitly releases it
+			let _ = release_rx.lock().unwrap().recv();
+		});
+		// ----------
+		let background = db.clone();
+		let first = tokio::spawn(async move {
+			let mut tx = background.begin(true).await;
+			tx.set("a", "1").unwrap();
+			tx.commit().await.unwrap();
+		});
+		// Wait until the first commit is blocked inside the observer callback
+		entered.notified().await;
+		// A second, disjoint writer must still commit promptly, since the commit
+		// lock is no longer held while observers are notified
+		let mut tx = db.begin(true).await;
+		tx.set("b", "2").unwrap();
+		tokio::time::timeout(std::time::Duration::from_secs(1), tx.commit())
+			.await
+			.expect("second commit should not be blocked by a slow observer")
+			.unwrap();
+		// ----------
+		release_tx.send(()).unwrap();
+		first.await.unwrap();
+	}
+
+	#[tokio::test]
+	async fn observers_receive_updated_and_removed_diffs() {
+		let db: Database<&str, &str> = new();
+		let mut tx = db.begin(true).await;
+		tx.set("apple", "one").unwrap();
+		tx.set("banana", "two").unwrap();
+		tx.commit().await.unwrap();
+		let seen: Arc<std::sync::Mutex<Vec<crate::ChangeSet<&str, &str>>>> =
+			Arc::new(std::sync::Mutex::new(Vec::new()));
+		// ----------
+		let inside = seen.clone();
+		let _handle = db.observe("a".."n", move |changes| {
+			inside.lock().unwrap().push(changes.clone());
+		});
+		// ----------
+		let mut tx = db.begin(true).await;
+		tx.set("apple", "updated").unwrap();
+		tx.del("banana").unwrap();
+		tx.commit().await.unwrap();
+		// ----------
+		let seen = seen.lock().unwrap();
+		assert_eq!(seen.len(), 1);
+		assert_eq!(seen[0].updated, vec![("apple", "one", "updated")]);
+		assert_eq!(seen[0].removed, vec![("banana", "two")]);
+	}
+
+	#[tokio::test]
+	async fn dropped_observer_handle_stops_notifications() {
+		let db: Database<&str, &str> = new();
+		let seen: Arc<std::sync::Mutex<Vec<crate::ChangeSet<&str, &str>>>> =
+			Arc::new(std::sync::Mutex::new(Vec::new()));
+		// ----------
+		let inside = seen.clone();
+		let handle = db.observe("a".."n", move |changes| {
+			inside.lock().unwrap().push(changes.clone());
+		});
+		drop(handle);
+		// ----------
+		let mut tx = db.begin(true).await;
+		tx.set("apple", "one").unwrap();
+		tx.commit().await.unwrap();
+		// ----------
+		assert!(seen.lock().unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn cursor_seeks_and_iterates_both_ways() {
+		use crate::Direction;
+		let db: Database<&str, &str> = new();
+		// ----------
+		let mut tx = db.begin(true).await;
+		tx.set("a", "1").unwrap();
+		tx.set("b", "2").unwrap();
+		tx.set("c", "3").unwrap();
+		tx.set("d", "4").unwrap();
+		tx.commit().await.unwrap();
+		// ----------
+		let mut tx = db.begin(false).await;
+		let mut cursor = tx.cursor("a".."z").unwrap();
+		cursor.seek("b");
+		assert_eq!(cursor.next(), Some((&"b", &"2")));
+		assert_eq!(cursor.next(), Some((&"c", &"3")));
+		// ----------
+		let mut cursor = tx.cursor("a".."z").unwrap();
+		cursor.direction(Direction::Reverse);
+		assert_eq!(cursor.next(), Some((&"d", &"4")));
+		assert_eq!(cursor.next(), Some((&"c", &"3")));
+	}
+
+	#[tokio::test]
+	async fn cursor_reseeks_backwards_within_range() {
+		let db: Database<&str, &str> = new();
+		// ----------
+		let mut tx = db.begin(true).await;
+		tx.set("a", "1").unwrap();
+		tx.set("b", "2").unwrap();
+		tx.set("c", "3").unwrap();
+		tx.set("d", "4").unwrap();
+		tx.commit().await.unwrap();
+		// ----------
+		let mut tx = db.begin(false).await;
+		let mut cursor = tx.cursor("a".."z").unwrap();
+		cursor.seek("m");
+		assert_eq!(cursor.next(), None);
+		// Seeking backwards, but still within the cursor's original range,
+		// must reposition to the earlier key rather than keep the stale seek
+		cursor.seek("c");
+		assert_eq!(cursor.next(), Some((&"c", &"3")));
+		assert_eq!(cursor.next(), Some((&"d", &"4")));
+	}
+
+	#[tokio::test]
+	async fn two_sequential_cursors_on_one_write_transaction() {
+		let db: Database<&str, &str> = new();
+		// ----------
+		let mut tx = db.begin(true).await;
+		tx.set("a", "1").unwrap();
+		tx.set("b", "2").unwrap();
+		tx.set("c", "3").unwrap();
+		tx.commit().await.unwrap();
+		// ----------
+		// Neither cursor is explicitly dropped before the next is obtained
+		let mut tx = db.begin(true).await;
+		let mut cursor = tx.cursor("a".."z").unwrap();
+		assert_eq!(cursor.next(), Some((&"a", &"1")));
+		let mut cursor = tx.cursor("a".."z").unwrap();
+		assert_eq!(cursor.next(), Some((&"a", &"1")));
+		assert_eq!(cursor.next(), Some((&"b", &"2")));
+		tx.cancel().unwrap();
+	}
+
+	#[tokio::test]
+	async fn transaction_runner_commits_and_fires_callback() {
+		use crate::TxOutcome;
+		let db: Database<&str, &str> = new();
+		let fired = Arc::new(std::sync::atomic::AtomicBool::new(false));
+		// ----------
+		let inside = fired.clone();
+		let res = db
+			.transaction(true, |tx| {
+				tx.set("test", "something")?;
+				tx.on_commit({
+					let inside = inside.clone();
+					move || inside.store(true, std::sync::atomic::Ordering::SeqCst)
+				});
+				Ok(TxOutcome::Commit(()))
+			})
+			.await;
+		assert!(res.is_ok());
+		assert!(fired.load(std::sync::atomic::Ordering::SeqCst));
+		// ----------
+		let mut tx = db.begin(false).await;
+		assert_eq!(tx.get("test").unwrap(), Some("something"));
+		tx.cancel().unwrap();
+	}
+
+	#[tokio::test]
+	async fn transaction_runner_aborts_without_committing() {
+		use crate::TxOutcome;
+		let db: Database<&str, &str> = new();
+		let fired = Arc::new(std::sync::atomic::AtomicBool::new(false));
+		// ----------
+		let inside = fired.clone();
+		let res: Result<(), Error> = db
+			.transaction(true, |tx| {
+				tx.set("test", "something")?;
+				tx.on_commit({
+					let inside = inside.clone();
+					move || inside.store(true, std::sync::atomic::Ordering::SeqCst)
+				});
+				Ok(TxOutcome::Abort(()))
+			})
+			.await;
+		assert!(res.is_ok());
+		assert!(!fired.load(std::sync::atomic::Ordering::SeqCst));
+		// ----------
+		let mut tx = db.begin(false).await;
+		assert_eq!(tx.get("test").unwrap(), None);
+		tx.cancel().unwrap();
+	}
+
+	/// A unique scratch file path for a file-backed database test, removed on drop
+	struct TempPath(PathBuf);
+
+	impl TempPath {
+		fn new(name: &str) -> TempPath {
+			let nanos = std::time::SystemTime::now()
+				.duration_since(std::time::UNIX_EPOCH)
+				.unwrap()
+				.as_nanos();
+			TempPath(std::env::temp_dir().join(format!("echodb-{name}-{}-{nanos}.db", std::process::id())))
+		}
+	}
+
+	impl Drop for TempPath {
+		fn drop(&mut self) {
+			let _ = std::fs::remove_file(&self.0);
+			let mut wal = self.0.as_os_str().to_owned();
+			wal.push(".wal");
+			let _ = std::fs::remove_file(wal);
+		}
+	}
+
+	#[tokio::test]
+	async fn file_backed_database_reloads_committed_data_on_reopen() {
+		let path = TempPath::new("reopen");
+		// ----------
+		let db: Database<String, String> = open(Config::file(&path.0)).await.unwrap();
+		let mut tx = db.begin(true).await;
+		tx.set("test".to_string(), "something".to_string()).unwrap();
+		tx.commit().await.unwrap();
+		drop(db);
+		// ----------
+		let db: Database<String, String> = open(Config::file(&path.0)).await.unwrap();
+		let mut tx = db.begin(false).await;
+		assert_eq!(tx.get("test".to_string()).unwrap(), Some("something".to_string()));
+		tx.cancel().unwrap();
+	}
+
+	#[tokio::test]
+	async fn file_backed_database_replays_trailing_wal_records() {
+		let path = TempPath::new("replay");
+		// ----------
+		let db: Database<String, String> =
+			open(Config::file(&path.0).with_sync(SyncPolicy::Never)).await.unwrap();
+		let mut tx = db.begin(true).await;
+		tx.set("one".to_string(), "a".to_string()).unwrap();
+		tx.commit().await.unwrap();
+		let mut tx = db.begin(true).await;
+		tx.set("two".to_string(), "b".to_string()).unwrap();
+		tx.commit().await.unwrap();
+		// No flush happened, so both commits only exist as write-ahead log records
+		drop(db);
+		// ----------
+		let db: Database<String, String> = open(Config::file(&path.0)).await.unwrap();
+		let mut tx = db.begin(false).await;
+		assert_eq!(tx.get("one".to_string()).unwrap(), Some("a".to_string()));
+		assert_eq!(tx.get("two".to_string()).unwrap(), Some("b".to_string()));
+		tx.cancel().unwrap();
+	}
+
+	#[tokio::test]
+	async fn file_backed_database_reloads_after_flush_folds_the_log() {
+		let path = TempPath::new("flush");
+		// ----------
+		let db: Database<String, String> =
+			open(Config::file(&path.0).with_sync(SyncPolicy::Never)).await.unwrap();
+		let mut tx = db.begin(true).await;
+		tx.set("test".to_string(), "something".to_string()).unwrap();
+		tx.commit().await.unwrap();
+		db.flush().await.unwrap();
+		drop(db);
+		// ----------
+		let db: Database<String, String> = open(Config::file(&path.0)).await.unwrap();
+		let mut tx = db.begin(false).await;
+		assert_eq!(tx.get("test".to_string()).unwrap(), Some("something".to_string()));
+		tx.cancel().unwrap();
+	}
+
+	#[tokio::test]
+	async fn failed_fold_does_not_fail_commit_or_skip_callbacks() {
+		let nanos = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.unwrap()
+			.as_nanos();
+		let dir = std::env::temp_dir()
+			.join(format!("echodb-fold-failure-{}-{nanos}", std::process::id()));
+		std::fs::create_dir(&dir).unwrap();
+		let path = dir.join("db");
+		let db: Database<String, String> = open(Config::file(&path)).await.unwrap();
+		// Remove the directory out from under the database, so the fold this
+		// commit triggers cannot create its snapshot file
+		std::fs::remove_dir_all(&dir).unwrap();
+		// ----------
+		let fired = Arc::new(std::sync::atomic::AtomicBool::new(false));
+		let inside = fired.clone();
+		let mut tx = db.begin(true).await;
+		tx.set("test".to_string(), "something".to_string()).unwrap();
+		tx.on_commit(move || inside.store(true, std::sync::atomic::Ordering::SeqCst));
+		let res = tx.commit().await;
+		// The commit itself is durable via the write-ahead log, so it succeeds
+		// and its callbacks run, even though the fold that followed it failed
+		assert!(res.is_ok());
+		assert!(fired.load(std::sync::atomic::Ordering::SeqCst));
+		// ----------
+		let mut tx = db.begin(false).await;
+		assert_eq!(tx.get("test".to_string()).unwrap(), Some("something".to_string()));
+		tx.cancel().unwrap();
+	}
+
+	#[tokio::test]
+	async fn periodic_sync_task_stops_once_database_is_dropped() {
+		let path = TempPath::new("periodic");
+		let db: Database<String, String> = open(
+			Config::file(&path.0).with_sync(SyncPolicy::Periodic(std::time::Duration::from_millis(10))),
+		)
+		.await
+		.unwrap();
+		// The background task holds its own clone of `db`, so this weak handle
+		// only stops upgrading once that clone is dropped too
+		let weak = Arc::downgrade(&db.writelock);
+		drop(db);
+		// Give the background task a couple of intervals to notice and exit
+		tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+		assert!(weak.upgrade().is_none());
+	}
 }
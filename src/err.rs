@@ -0,0 +1,52 @@
+// Copyright © SurrealDB Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module stores the error type returned by this crate.
+
+use std::fmt;
+
+/// An error which occurred when working with a transaction
+#[derive(Debug)]
+pub enum Error {
+	/// The transaction has already been committed or cancelled
+	TxClosed,
+	/// The transaction is not writable
+	TxNotWritable,
+	/// The key already exists in the database
+	KeyAlreadyExists,
+	/// The value did not match the expected value
+	ValNotExpectedValue,
+	/// The transaction conflicted with another transaction which committed first
+	Conflict,
+	/// An I/O error occurred while reading or writing the persisted database
+	Io(std::io::Error),
+	/// A serialization or deserialization error occurred in the persisted database
+	Serde(String),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::TxClosed => write!(f, "The transaction has been closed"),
+			Error::TxNotWritable => write!(f, "The transaction is not writable"),
+			Error::KeyAlreadyExists => write!(f, "The key already exists"),
+			Error::ValNotExpectedValue => write!(f, "The value was not the expected value"),
+			Error::Conflict => write!(f, "The transaction conflicted with a concurrent write"),
+			Error::Io(e) => write!(f, "An I/O error occurred: {e}"),
+			Error::Serde(e) => write!(f, "A serialization error occurred: {e}"),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
@@ -14,16 +14,29 @@
 
 #![allow(clippy::bool_comparison)]
 
+mod config;
+mod cursor;
 mod db;
 mod err;
+mod run;
+mod sub;
 mod tx;
+mod wal;
 
 #[cfg(test)]
 pub(crate) mod kv;
 
+#[doc(inline)]
+pub use self::config::*;
+#[doc(inline)]
+pub use self::cursor::*;
 #[doc(inline)]
 pub use self::db::*;
 #[doc(inline)]
 pub use self::err::*;
 #[doc(inline)]
+pub use self::run::*;
+#[doc(inline)]
+pub use self::sub::*;
+#[doc(inline)]
 pub use self::tx::*;
@@ -0,0 +1,91 @@
+// Copyright © SurrealDB Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module stores the closure-driven transaction runner.
+
+use crate::db::Database;
+use crate::err::Error;
+use crate::tx::Transaction;
+use std::fmt::Debug;
+
+/// The outcome of a closure run via [`Database::transaction`]
+pub enum TxOutcome<R> {
+	/// Commit the transaction, returning this value to the caller
+	Commit(R),
+	/// Cancel the transaction, returning this value to the caller instead
+	Abort(R),
+}
+
+/// The default number of times [`Database::transaction`] retries a write
+/// conflict before giving up
+pub const DEFAULT_RETRIES: usize = 5;
+
+impl<K, V> Database<K, V>
+where
+	K: Ord + Clone + Debug + Sync + Send + 'static,
+	V: Eq + Clone + Debug + Sync + Send + 'static,
+{
+	/// Run a closure inside a transaction
+	///
+	/// The closure returns [`TxOutcome::Commit`] to commit and return a value,
+	/// or [`TxOutcome::Abort`] to cancel and return a value instead, so
+	/// application-level rollback no longer requires manual `cancel` calls.
+	/// A read-only transaction cannot be committed, so [`TxOutcome::Commit`]
+	/// from one returns [`Error::TxNotWritable`] instead of the value.
+	/// Write transactions are retried, with a fresh snapshot, up to
+	/// [`DEFAULT_RETRIES`] times if the commit conflicts with another writer.
+	pub async fn transaction<F, R>(&self, write: bool, f: F) -> Result<R, Error>
+	where
+		F: FnMut(&mut Transaction<K, V>) -> Result<TxOutcome<R>, Error>,
+	{
+		self.transaction_with_retries(write, DEFAULT_RETRIES, f).await
+	}
+
+	/// Run a closure inside a transaction, retrying a write conflict up to `retries` times
+	pub async fn transaction_with_retries<F, R>(
+		&self,
+		write: bool,
+		retries: usize,
+		mut f: F,
+	) -> Result<R, Error>
+	where
+		F: FnMut(&mut Transaction<K, V>) -> Result<TxOutcome<R>, Error>,
+	{
+		let mut attempt = 0;
+		loop {
+			let mut tx = self.begin(write).await;
+			let outcome = match f(&mut tx) {
+				Ok(outcome) => outcome,
+				Err(e) => {
+					let _ = tx.cancel();
+					return Err(e);
+				}
+			};
+			match outcome {
+				TxOutcome::Commit(val) => match tx.commit().await {
+					Ok(()) => return Ok(val),
+					Err(Error::Conflict) if attempt < retries => {
+						attempt += 1;
+						continue;
+					}
+					Err(e) => return Err(e),
+				},
+				TxOutcome::Abort(val) => {
+					let _ = tx.cancel();
+					return Ok(val);
+				}
+			}
+		}
+	}
+}
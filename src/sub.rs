@@ -0,0 +1,173 @@
+// Copyright © SurrealDB Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module stores change observers and the diffs delivered to them.
+
+use imbl::ordmap::DiffItem;
+use imbl::OrdMap;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The set of changes delivered to an observer after a committed transaction
+#[derive(Clone, Debug)]
+pub struct ChangeSet<K, V> {
+	/// Keys which did not exist before this commit and now do
+	pub added: Vec<(K, V)>,
+	/// Keys which existed before this commit and whose value changed
+	pub updated: Vec<(K, V, V)>,
+	/// Keys which existed before this commit and have now been removed
+	pub removed: Vec<(K, V)>,
+}
+
+impl<K, V> ChangeSet<K, V> {
+	/// Create a new, empty change-set
+	fn empty() -> ChangeSet<K, V> {
+		ChangeSet {
+			added: Vec::new(),
+			updated: Vec::new(),
+			removed: Vec::new(),
+		}
+	}
+
+	/// Check whether this change-set contains no changes at all
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+	}
+}
+
+type Callback<K, V> = Arc<dyn Fn(&ChangeSet<K, V>) + Send + Sync>;
+
+/// A single registered observer
+struct Observer<K, V> {
+	/// The key range this observer is interested in
+	range: Range<K>,
+	/// The callback to invoke with the filtered change-set
+	callback: Callback<K, V>,
+}
+
+/// The registry of change observers for a [`Database`](crate::Database)
+#[derive(Clone)]
+pub(crate) struct Subscriptions<K, V> {
+	/// The next observer id to hand out
+	next: Arc<AtomicU64>,
+	/// The currently registered observers, keyed by id
+	observers: Arc<Mutex<HashMap<u64, Observer<K, V>>>>,
+}
+
+impl<K, V> Subscriptions<K, V>
+where
+	K: Ord + Clone + Debug,
+	V: Clone + PartialEq,
+{
+	/// Create a new, empty observer registry
+	pub(crate) fn new() -> Subscriptions<K, V> {
+		Subscriptions {
+			next: Arc::new(AtomicU64::new(0)),
+			observers: Arc::new(Mutex::new(HashMap::new())),
+		}
+	}
+
+	/// Register a new observer for the given key range
+	pub(crate) fn register<F>(&self, range: Range<K>, callback: F) -> Handle<K, V>
+	where
+		F: Fn(&ChangeSet<K, V>) + Send + Sync + 'static,
+	{
+		let id = self.next.fetch_add(1, Ordering::SeqCst);
+		let observer = Observer {
+			range,
+			callback: Arc::new(callback),
+		};
+		self.observers.lock().unwrap().insert(id, observer);
+		Handle {
+			id,
+			observers: self.observers.clone(),
+		}
+	}
+
+	/// Compute the diff between the previous and new committed snapshot, and
+	/// deliver it to every observer whose range overlaps the changed keys
+	///
+	/// The filtered change-set for each interested observer is computed while
+	/// the registry lock is held, but every callback is invoked only after
+	/// that lock has been released, so a slow observer cannot block another
+	/// commit from registering, unregistering or notifying observers.
+	pub(crate) fn notify(&self, before: &OrdMap<K, V>, after: &OrdMap<K, V>) {
+		let deliveries: Vec<(Callback<K, V>, ChangeSet<K, V>)> = {
+			let observers = self.observers.lock().unwrap();
+			if observers.is_empty() {
+				return;
+			}
+			let mut all = ChangeSet::empty();
+			for item in before.diff(after) {
+				match item {
+					DiffItem::Add(k, v) => all.added.push((k.clone(), v.clone())),
+					DiffItem::Update {
+						old,
+						new,
+					} => all.updated.push((new.0.clone(), old.1.clone(), new.1.clone())),
+					DiffItem::Remove(k, v) => all.removed.push((k.clone(), v.clone())),
+				}
+			}
+			if all.is_empty() {
+				return;
+			}
+			observers
+				.values()
+				.filter_map(|observer| {
+					let filtered = ChangeSet {
+						added: all
+							.added
+							.iter()
+							.filter(|(k, _)| observer.range.contains(k))
+							.cloned()
+							.collect(),
+						updated: all
+							.updated
+							.iter()
+							.filter(|(k, _, _)| observer.range.contains(k))
+							.cloned()
+							.collect(),
+						removed: all
+							.removed
+							.iter()
+							.filter(|(k, _)| observer.range.contains(k))
+							.cloned()
+							.collect(),
+					};
+					(!filtered.is_empty()).then(|| (observer.callback.clone(), filtered))
+				})
+				.collect()
+		};
+		for (callback, filtered) in deliveries {
+			callback(&filtered);
+		}
+	}
+}
+
+/// A handle to a registered observer
+///
+/// The observer is automatically unregistered when this handle is dropped.
+pub struct Handle<K, V> {
+	id: u64,
+	observers: Arc<Mutex<HashMap<u64, Observer<K, V>>>>,
+}
+
+impl<K, V> Drop for Handle<K, V> {
+	fn drop(&mut self) {
+		self.observers.lock().unwrap().remove(&self.id);
+	}
+}
@@ -14,18 +14,25 @@
 
 //! This module stores the database transaction logic.
 
+use crate::config::SyncPolicy;
+use crate::cursor::{Cursor, Direction};
 use crate::err::Error;
 use crate::Database;
 use imbl::ordmap::Entry;
 use imbl::OrdMap;
+use imbl::OrdSet;
 use std::borrow::Borrow;
 use std::fmt::Debug;
-use std::mem::drop;
 use std::ops::Range;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tokio::sync::OwnedMutexGuard;
 
 /// A serializable snapshot isolated database transaction
+///
+/// Write transactions run optimistically: no lock is held while the
+/// transaction is open. Instead the keys read and written are tracked, and
+/// validated for conflicts against any other transaction which may have
+/// committed in the meantime when this transaction itself commits.
 pub struct Transaction<K, V>
 where
 	K: Ord + Clone + Debug + Sync + Send + 'static,
@@ -35,12 +42,23 @@ where
 	done: bool,
 	/// Is the transaction writeable?
 	write: bool,
-	/// The current snapshot for this transaction
+	/// The datastore version this transaction's snapshot was cloned from
+	version: u64,
+	/// The commit version each key had when this transaction's snapshot was
+	/// taken, used to detect conflicts without the ABA problem of comparing
+	/// values (a key can change away from and back to its original value
+	/// between this transaction's snapshot and its commit)
+	base_versions: OrdMap<K, u64>,
+	/// The current snapshot for this transaction, including any uncommitted writes
 	snapshot: OrdMap<K, V>,
+	/// The keys read by this transaction, via `get`, `exists`, `keys` and `scan`
+	reads: OrdSet<K>,
+	/// The keys written by this transaction, via `set`, `put`, `putc`, `del` and `delc`
+	writes: OrdMap<K, Option<V>>,
+	/// Callbacks registered with `on_commit`, run only if this transaction commits
+	callbacks: Vec<Box<dyn FnOnce() + Send>>,
 	/// The parent database for this transaction
 	database: Database<K, V>,
-	/// The parent datastore transaction write lock
-	writelock: Option<OwnedMutexGuard<()>>,
 }
 
 impl<K, V> Transaction<K, V>
@@ -49,26 +67,35 @@ where
 	V: Eq + Clone + Debug + Sync + Send + 'static,
 {
 	/// Create a new read-only transaction
-	pub(crate) fn read(db: Database<K, V>, lock: Option<OwnedMutexGuard<()>>) -> Transaction<K, V> {
+	pub(crate) fn read(db: Database<K, V>) -> Transaction<K, V> {
+		let base = (*(*db.datastore.load())).clone();
+		let base_versions = (*(*db.versions.load())).clone();
 		Transaction {
 			done: false,
 			write: false,
-			snapshot: (*(*db.datastore.load())).clone(),
+			version: db.version.load(Ordering::SeqCst),
+			base_versions,
+			snapshot: base.clone(),
+			reads: OrdSet::new(),
+			writes: OrdMap::new(),
+			callbacks: Vec::new(),
 			database: db,
-			writelock: lock,
 		}
 	}
 	/// Create a new writeable transaction
-	pub(crate) fn write(
-		db: Database<K, V>,
-		lock: Option<OwnedMutexGuard<()>>,
-	) -> Transaction<K, V> {
+	pub(crate) fn write(db: Database<K, V>) -> Transaction<K, V> {
+		let base = (*(*db.datastore.load())).clone();
+		let base_versions = (*(*db.versions.load())).clone();
 		Transaction {
 			done: false,
 			write: true,
-			snapshot: (*(*db.datastore.load())).clone(),
+			version: db.version.load(Ordering::SeqCst),
+			base_versions,
+			snapshot: base.clone(),
+			reads: OrdSet::new(),
+			writes: OrdMap::new(),
+			callbacks: Vec::new(),
 			database: db,
-			writelock: lock,
 		}
 	}
 
@@ -77,6 +104,17 @@ where
 		self.done
 	}
 
+	/// Register a callback to run only if this transaction successfully commits
+	///
+	/// Callbacks are discarded, unrun, if the transaction is cancelled or
+	/// aborted instead.
+	pub fn on_commit<F>(&mut self, callback: F)
+	where
+		F: FnOnce() + Send + 'static,
+	{
+		self.callbacks.push(Box::new(callback));
+	}
+
 	/// Cancel the transaction and rollback any changes
 	pub fn cancel(&mut self) -> Result<(), Error> {
 		// Check to see if transaction is closed
@@ -85,16 +123,23 @@ where
 		}
 		// Mark this transaction as done
 		self.done = true;
-		// Release the commit lock
-		if let Some(lock) = self.writelock.take() {
-			drop(lock);
-		}
+		// Discard any registered on-commit callbacks without running them
+		self.callbacks.clear();
 		// Continue
 		Ok(())
 	}
 
 	/// Commit the transaction and store all changes
-	pub fn commit(&mut self) -> Result<(), Error> {
+	///
+	/// This briefly acquires the datastore commit lock to apply this
+	/// transaction's write-set on top of the latest committed snapshot. If
+	/// another transaction has committed since this one began, the read-set
+	/// and write-set are validated against that newer snapshot, and
+	/// `Error::Conflict` is returned if anything this transaction touched has
+	/// since changed. For a persistent database, the write-set is appended to
+	/// the write-ahead log before it becomes visible to other transactions, so
+	/// a failed append is reported as a failed commit with nothing mutated.
+	pub async fn commit(&mut self) -> Result<(), Error> {
 		// Check to see if transaction is closed
 		if self.done == true {
 			return Err(Error::TxClosed);
@@ -105,18 +150,90 @@ where
 		}
 		// Mark this transaction as done
 		self.done = true;
-		// Atomically update the datastore using ArcSwap
-		self.database.datastore.store(Arc::new(self.snapshot.clone()));
-		// Release the commit lock
-		if let Some(lock) = self.writelock.take() {
-			drop(lock);
+		// Briefly acquire the commit lock to apply this transaction
+		let lock = self.database.writelock.clone().lock_owned().await;
+		// Load the latest committed datastore
+		let current = self.database.datastore.load();
+		// If another transaction has committed since we began, validate for conflicts.
+		// Keys are compared by the commit version they were last written at, not by
+		// value, so a key which changed away from and back to its original value
+		// while this transaction was open (the ABA problem) is still caught
+		if self.database.version.load(Ordering::SeqCst) != self.version {
+			let current_versions = self.database.versions.load();
+			for key in self.reads.iter().chain(self.writes.keys()) {
+				if current_versions.get(key) != self.base_versions.get(key) {
+					drop(lock);
+					return Err(Error::Conflict);
+				}
+			}
+		}
+		// Apply this transaction's write-set on top of the latest datastore
+		let mut next = (**current).clone();
+		for (key, val) in self.writes.iter() {
+			match val {
+				Some(val) => {
+					next.insert(key.clone(), val.clone());
+				}
+				None => {
+					next.remove(key);
+				}
+			}
+		}
+		// Append this commit's write-set to the write-ahead log, if persistent,
+		// before anything becomes visible, so a failed append leaves the
+		// datastore untouched rather than reporting a failed commit that in
+		// fact took effect. The log's lock is held until the new snapshot is
+		// stored, so a fold never truncates a logged write it cannot yet see
+		let wal = match &self.database.persistence {
+			Some(p) => {
+				let mut wal = p.wal.lock().await;
+				(p.append)(&mut wal, &self.writes)?;
+				Some(wal)
+			}
+			None => None,
+		};
+		// Bump the datastore version and record it against every key this
+		// transaction wrote, so later transactions can detect a conflict on
+		// this key even if its value has since changed back to what it was
+		let new_version = self.database.version.fetch_add(1, Ordering::SeqCst) + 1;
+		let mut next_versions = (**self.database.versions.load()).clone();
+		for key in self.writes.keys() {
+			next_versions.insert(key.clone(), new_version);
+		}
+		// Atomically store the new snapshot and its key versions
+		self.database.datastore.store(Arc::new(next.clone()));
+		self.database.versions.store(Arc::new(next_versions));
+		drop(wal);
+		// Release the commit lock now that the mutation itself is complete, so a
+		// slow observer or a log fold below does not block other committing writers
+		drop(lock);
+		// Notify any observers of the diff between the previous and new snapshot
+		self.database.subscriptions.notify(&current, &next);
+		// Run any on-commit callbacks now that the commit has succeeded. This
+		// happens before the fold below, so a fold failure can never cause a
+		// commit that has already taken effect to skip a caller's callbacks
+		for callback in self.callbacks.drain(..) {
+			callback();
+		}
+		// Fold the write-ahead log into a snapshot, now that the commit is durable.
+		// This is guarded by the write-ahead log's own lock, rather than the commit
+		// lock, so it can't race a concurrent commit's WAL append, and it folds the
+		// latest stored datastore, which holds every record in the log, rather than
+		// this commit's own snapshot. A failed fold is only a missed compaction
+		// opportunity, not a lost write, so it is not reported as a failed commit;
+		// it is retried on the next commit or flush
+		if let Some(p) = &self.database.persistence {
+			if matches!(p.sync, SyncPolicy::OnCommit) {
+				let _wal = p.wal.lock().await;
+				let _ = (p.fold)(&p.base, &self.database.datastore.load());
+			}
 		}
 		// Continue
 		Ok(())
 	}
 
 	/// Check if a key exists in the database
-	pub fn exists<Q>(&self, key: Q) -> Result<bool, Error>
+	pub fn exists<Q>(&mut self, key: Q) -> Result<bool, Error>
 	where
 		Q: Borrow<K>,
 	{
@@ -126,12 +243,16 @@ where
 		}
 		// Check the key
 		let res = self.snapshot.contains_key(key.borrow());
+		// Record this key in the read-set
+		if self.write {
+			self.reads.insert(key.borrow().clone());
+		}
 		// Return result
 		Ok(res)
 	}
 
 	/// Fetch a key from the database
-	pub fn get<Q>(&self, key: Q) -> Result<Option<V>, Error>
+	pub fn get<Q>(&mut self, key: Q) -> Result<Option<V>, Error>
 	where
 		Q: Borrow<K>,
 	{
@@ -141,6 +262,10 @@ where
 		}
 		// Get the key
 		let res = self.snapshot.get(key.borrow()).cloned();
+		// Record this key in the read-set
+		if self.write {
+			self.reads.insert(key.borrow().clone());
+		}
 		// Return result
 		Ok(res)
 	}
@@ -159,7 +284,10 @@ where
 			return Err(Error::TxNotWritable);
 		}
 		// Set the key
-		self.snapshot.insert(key.into(), val);
+		let key = key.into();
+		self.snapshot.insert(key.clone(), val.clone());
+		// Record this key in the write-set
+		self.writes.insert(key, Some(val));
 		// Return result
 		Ok(())
 	}
@@ -178,12 +306,15 @@ where
 			return Err(Error::TxNotWritable);
 		}
 		// Set the key
-		match self.snapshot.entry(key.into()) {
+		let key = key.into();
+		match self.snapshot.entry(key.clone()) {
 			Entry::Vacant(v) => {
-				v.insert(val);
+				v.insert(val.clone());
 			}
 			_ => return Err(Error::KeyAlreadyExists),
 		};
+		// Record this key in the write-set
+		self.writes.insert(key, Some(val));
 		// Return result
 		Ok(())
 	}
@@ -202,15 +333,18 @@ where
 			return Err(Error::TxNotWritable);
 		}
 		// Set the key
-		match (self.snapshot.entry(key.into()), &chk) {
+		let key = key.into();
+		match (self.snapshot.entry(key.clone()), &chk) {
 			(Entry::Occupied(mut v), Some(w)) if v.get() == w => {
-				v.insert(val);
+				v.insert(val.clone());
 			}
 			(Entry::Vacant(v), None) => {
-				v.insert(val);
+				v.insert(val.clone());
 			}
 			_ => return Err(Error::ValNotExpectedValue),
 		};
+		// Record this key in the write-set
+		self.writes.insert(key, Some(val));
 		// Return result
 		Ok(())
 	}
@@ -229,7 +363,10 @@ where
 			return Err(Error::TxNotWritable);
 		}
 		// Remove the key
-		self.snapshot.remove(key.borrow());
+		let key = key.borrow().clone();
+		self.snapshot.remove(&key);
+		// Record this key in the write-set
+		self.writes.insert(key, None);
 		// Return result
 		Ok(())
 	}
@@ -248,7 +385,8 @@ where
 			return Err(Error::TxNotWritable);
 		}
 		// Remove the key
-		match (self.snapshot.entry(key.into()), &chk) {
+		let key = key.into();
+		match (self.snapshot.entry(key.clone()), &chk) {
 			(Entry::Occupied(v), Some(w)) if v.get() == w => {
 				v.remove();
 			}
@@ -257,12 +395,19 @@ where
 			}
 			_ => return Err(Error::ValNotExpectedValue),
 		};
+		// Record this key in the write-set
+		self.writes.insert(key, None);
 		// Return result
 		Ok(())
 	}
 
-	/// Retrieve a range of keys from the databases
-	pub fn keys<Q>(&self, rng: Range<Q>, limit: usize) -> Result<Vec<K>, Error>
+	/// Obtain a lazy cursor over a range of this transaction's snapshot
+	///
+	/// Unlike `keys`/`scan`, nothing is materialized up front, and the cursor
+	/// can be seeked and iterated in either direction. In a write transaction,
+	/// every key the cursor yields is recorded in the read-set as it is
+	/// consumed, the same as `get`/`exists`/`keys`/`scan`.
+	pub fn cursor<Q>(&mut self, rng: Range<Q>) -> Result<Cursor<'_, K, V>, Error>
 	where
 		Q: Into<K>,
 	{
@@ -273,31 +418,44 @@ where
 		// Compute the range
 		let beg = rng.start.into();
 		let end = rng.end.into();
-		// Scan the keys
-		let res = self.snapshot.range(beg..end).take(limit).map(|(k, _)| k.clone()).collect();
+		// Track reads for a write transaction as the cursor yields entries
+		let reads = self.write.then_some(&mut self.reads);
+		// Return a forward cursor over this range
+		Ok(Cursor::new(&self.snapshot, beg..end, Direction::Forward, reads))
+	}
+
+	/// Retrieve a range of keys from the databases
+	pub fn keys<Q>(&mut self, rng: Range<Q>, limit: usize) -> Result<Vec<K>, Error>
+	where
+		Q: Into<K>,
+	{
+		// Drain a cursor over this range, up to the limit, recording reads as it goes
+		let mut cursor = self.cursor(rng)?;
+		let mut res = Vec::new();
+		while res.len() < limit {
+			match cursor.next() {
+				Some((k, _)) => res.push(k.clone()),
+				None => break,
+			}
+		}
 		// Return result
 		Ok(res)
 	}
 
 	/// Retrieve a range of key-value pairs from the databases
-	pub fn scan<Q>(&self, rng: Range<Q>, limit: usize) -> Result<Vec<(K, V)>, Error>
+	pub fn scan<Q>(&mut self, rng: Range<Q>, limit: usize) -> Result<Vec<(K, V)>, Error>
 	where
 		Q: Into<K>,
 	{
-		// Check to see if transaction is closed
-		if self.done == true {
-			return Err(Error::TxClosed);
+		// Drain a cursor over this range, up to the limit, recording reads as it goes
+		let mut cursor = self.cursor(rng)?;
+		let mut res = Vec::new();
+		while res.len() < limit {
+			match cursor.next() {
+				Some((k, v)) => res.push((k.clone(), v.clone())),
+				None => break,
+			}
 		}
-		// Compute the range
-		let beg = rng.start.into();
-		let end = rng.end.into();
-		// Scan the keys
-		let res = self
-			.snapshot
-			.range(beg..end)
-			.take(limit)
-			.map(|(k, v)| (k.clone(), v.clone()))
-			.collect();
 		// Return result
 		Ok(res)
 	}
@@ -0,0 +1,112 @@
+// Copyright © SurrealDB Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module stores the on-disk snapshot and write-ahead log format used
+//! by a file-backed [`Database`](crate::Database).
+
+use crate::err::Error;
+use imbl::OrdMap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// The path of the full snapshot file for a given base path
+fn snapshot_path(base: &Path) -> PathBuf {
+	base.to_path_buf()
+}
+
+/// The path of the write-ahead log file for a given base path
+fn wal_path(base: &Path) -> PathBuf {
+	let mut name = base.as_os_str().to_owned();
+	name.push(".wal");
+	PathBuf::from(name)
+}
+
+/// Rebuild a datastore from its last committed snapshot, plus any write-ahead
+/// log records appended since that snapshot was taken
+pub(crate) fn load<K, V>(base: &Path) -> Result<OrdMap<K, V>, Error>
+where
+	K: Ord + Clone + Debug + DeserializeOwned,
+	V: Eq + Clone + Debug + DeserializeOwned,
+{
+	// Load the last full snapshot, if one exists
+	let mut map = match File::open(snapshot_path(base)) {
+		Ok(file) => serde_json::from_reader(BufReader::new(file))
+			.map_err(|e| Error::Serde(e.to_string()))?,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => OrdMap::new(),
+		Err(e) => return Err(Error::Io(e)),
+	};
+	// Replay any write-ahead log records appended since that snapshot
+	match File::open(wal_path(base)) {
+		Ok(file) => {
+			for line in BufReader::new(file).lines() {
+				let line = line.map_err(Error::Io)?;
+				if line.is_empty() {
+					continue;
+				}
+				let writes: Vec<(K, Option<V>)> =
+					serde_json::from_str(&line).map_err(|e| Error::Serde(e.to_string()))?;
+				for (key, val) in writes {
+					match val {
+						Some(val) => {
+							map.insert(key, val);
+						}
+						None => {
+							map.remove(&key);
+						}
+					}
+				}
+			}
+		}
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+		Err(e) => return Err(Error::Io(e)),
+	}
+	Ok(map)
+}
+
+/// Open the write-ahead log file for appending, creating it if it doesn't exist
+pub(crate) fn open_wal(base: &Path) -> Result<File, Error> {
+	OpenOptions::new().create(true).append(true).open(wal_path(base)).map_err(Error::Io)
+}
+
+/// Append a committed transaction's write-set to the write-ahead log
+pub(crate) fn append<K, V>(file: &mut File, writes: &OrdMap<K, Option<V>>) -> Result<(), Error>
+where
+	K: Ord + Clone + Debug + Serialize,
+	V: Eq + Clone + Debug + Serialize,
+{
+	let entries: Vec<(&K, &Option<V>)> = writes.iter().collect();
+	let line = serde_json::to_string(&entries).map_err(|e| Error::Serde(e.to_string()))?;
+	writeln!(file, "{line}").map_err(Error::Io)?;
+	file.flush().map_err(Error::Io)
+}
+
+/// Fold the write-ahead log into a full snapshot file, then truncate the log
+pub(crate) fn fold<K, V>(base: &Path, map: &OrdMap<K, V>) -> Result<(), Error>
+where
+	K: Ord + Clone + Debug + Serialize,
+	V: Eq + Clone + Debug + Serialize,
+{
+	// Write the snapshot to a temporary file, then atomically rename it into place
+	let tmp = wal_path(base).with_extension("snap.tmp");
+	let file = File::create(&tmp).map_err(Error::Io)?;
+	serde_json::to_writer(file, map).map_err(|e| Error::Serde(e.to_string()))?;
+	std::fs::rename(&tmp, snapshot_path(base)).map_err(Error::Io)?;
+	// The snapshot now contains every record, so the log can be truncated
+	File::create(wal_path(base)).map_err(Error::Io)?;
+	Ok(())
+}